@@ -0,0 +1,103 @@
+//! Turning a string into a sequence of positioned glyphs.
+//!
+//! This walks a string, resolves each character to a glyph, and advances a pen
+//! along the baseline, handling line breaks and optional right-to-left runs so
+//! that a terminal or GUI can draw text without reimplementing cursor maths.
+
+use crate::{Font, Glyph};
+
+/// A single glyph placed at a pen position by [`Font::layout`].
+pub struct PositionedGlyph<'a> {
+    glyph: &'a Glyph,
+    x: isize,
+    y: usize,
+    advance: usize,
+}
+
+impl<'a> PositionedGlyph<'a> {
+    /// The glyph to draw.
+    pub fn glyph(&self) -> &'a Glyph { self.glyph }
+    /// The x pen position in pixels, measured from the layout origin.
+    /// This may be negative for right-to-left runs.
+    pub fn x(&self) -> isize { self.x }
+    /// The y pen position in pixels of the top of the glyph's line.
+    pub fn y(&self) -> usize { self.y }
+    /// The horizontal advance in pixels applied for this glyph.
+    pub fn advance(&self) -> usize { self.advance }
+}
+
+/// An iterator over the positioned glyphs of a laid-out string.
+///
+/// Created by [`Font::layout`].
+pub struct Layout<'a> {
+    font: &'a Font,
+    chars: core::str::Chars<'a>,
+    /// The x position a new line returns to.
+    origin: isize,
+    x: isize,
+    y: usize,
+    rtl: bool,
+}
+
+impl<'a> Iterator for Layout<'a> {
+    type Item = PositionedGlyph<'a>;
+
+    fn next(&mut self) -> Option<PositionedGlyph<'a>> {
+        loop {
+            let c = self.chars.next()?;
+
+            if c == '\n' {
+                self.x = self.origin;
+                self.y += self.font.height();
+                continue;
+            }
+
+            // Characters without a glyph still advance the pen by the nominal
+            // width so that surrounding text keeps its spacing.
+            let (glyph, advance) = match self.font.lookup(c) {
+                Some(glyph) => (Some(glyph), glyph.advance()),
+                None => (None, self.font.width()),
+            };
+
+            // In a right-to-left run the pen moves left, so the advance is
+            // applied before the glyph is placed rather than after.
+            let x = if self.rtl {
+                self.x -= advance as isize;
+                self.x
+            } else {
+                let x = self.x;
+                self.x += advance as isize;
+                x
+            };
+
+            match glyph {
+                Some(glyph) => return Some(PositionedGlyph { glyph, x, y: self.y, advance }),
+                None => continue,
+            }
+        }
+    }
+}
+
+impl Font {
+    /// Lay out a string left-to-right, yielding a [`PositionedGlyph`] for each
+    /// character that has a glyph in this font.
+    ///
+    /// `\n` begins a new line, resetting the x position and advancing y by the
+    /// font's height.
+    pub fn layout<'a>(&'a self, text: &'a str) -> Layout<'a> {
+        self.layout_directional(text, false)
+    }
+
+    /// Lay out a string like [`Font::layout`], but lay glyphs out from a right
+    /// origin when `rtl` is set, advancing the pen leftwards.
+    pub fn layout_directional<'a>(&'a self, text: &'a str, rtl: bool) -> Layout<'a> {
+        Layout {
+            font: self,
+            chars: text.chars(),
+            origin: 0,
+            x: 0,
+            y: 0,
+            rtl,
+        }
+    }
+}
@@ -2,6 +2,33 @@
 extern crate alloc;
 
 use alloc::vec::Vec;
+use core::convert::TryInto;
+
+pub mod layout;
+
+pub use layout::PositionedGlyph;
+
+/// An error encountered while parsing a PC screen font.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// The font data ended before a complete font could be read.
+    Truncated,
+    /// The font did not begin with a recognised PSF1 or PSF2 magic number.
+    BadMagic,
+    /// The unicode table referred to a glyph index outside the glyph table.
+    GlyphIndexOutOfRange(usize),
+    /// A byte sequence in the unicode table was not valid UTF-8.
+    InvalidUtf8,
+}
+
+/// Read a little-endian `u32` at `offset`, failing if it runs past the end.
+fn read_u32(font: &[u8], offset: usize) -> Result<usize, ParseError> {
+    let end = offset + 4;
+    if end > font.len() {
+        return Err(ParseError::Truncated);
+    }
+    Ok(u32::from_le_bytes(font[offset..end].try_into().unwrap()) as usize)
+}
 
 pub struct Font {
     /// The width in pixels of this font's bounding box.
@@ -10,9 +37,12 @@ pub struct Font {
     height: usize,
     /// Every single glyph in the font.
     glyphs: Vec<Glyph>,
-    // TODO: Replace this with a proper associative structure.
-    /// A map between unicode characters and indexes into the glyph vec.
+    /// A map between unicode characters and indexes into the glyph vec,
+    /// sorted by `char` so that lookups can binary search it.
     unicode: Vec<UnicodeMap>,
+    /// A map between multi-codepoint sequences (combining marks or ligatures)
+    /// and indexes into the glyph vec, sorted by the sequence for binary search.
+    sequences: Vec<SequenceMap>,
 }
 
 /// Associates a unicode character and a glyph.
@@ -22,6 +52,13 @@ struct UnicodeMap {
     i: usize,
 }
 
+/// Associates a multi-codepoint sequence and a glyph.
+struct SequenceMap {
+    chars: Vec<char>,
+    // The index of the glyph.
+    i: usize,
+}
+
 pub struct Glyph {
     /// A set bit indicates that a pixel should be drawn for this glyph.
     bitmap: Vec<u8>,
@@ -31,9 +68,53 @@ pub struct Glyph {
     width: usize,
     /// See the docs for `Glyph::height`.
     height: usize,
+    /// See the docs for `Glyph::ink_width`.
+    ink_width: usize,
+    /// See the docs for `Glyph::ink_height`.
+    ink_height: usize,
+    /// See the docs for `Glyph::bearing_x`.
+    bearing_x: usize,
+    /// See the docs for `Glyph::bearing_y`.
+    bearing_y: usize,
+    /// See the docs for `Glyph::advance`.
+    advance: usize,
 }
 
 impl Glyph {
+    /// Build a glyph from its bitmap, scanning for the tight ink bounds.
+    ///
+    /// `advance` is the horizontal pen advance to associate with the glyph;
+    /// callers pass the font's nominal width as the default.
+    fn new(bitmap: Vec<u8>, line_size: usize, width: usize, height: usize, advance: usize) -> Glyph {
+        let mut min_x = width;
+        let mut min_y = height;
+        let mut max_x = 0;
+        let mut max_y = 0;
+        let mut inked = false;
+        for y in 0..height {
+            for x in 0..width {
+                let (line_byte_index, bit_index) = num_integer::div_rem(x, 8);
+                let mask = 0b10000000 >> bit_index;
+                if bitmap[y * line_size + line_byte_index] & mask > 0 {
+                    inked = true;
+                    if x < min_x { min_x = x; }
+                    if x > max_x { max_x = x; }
+                    if y < min_y { min_y = y; }
+                    if y > max_y { max_y = y; }
+                }
+            }
+        }
+
+        let (ink_width, ink_height, bearing_x, bearing_y) = if inked {
+            (max_x - min_x + 1, max_y - min_y + 1, min_x, min_y)
+        } else {
+            // A blank glyph such as the space has no ink and no meaningful offsets.
+            (0, 0, 0, 0)
+        };
+
+        Glyph { bitmap, line_size, width, height, ink_width, ink_height, bearing_x, bearing_y, advance }
+    }
+
     /// The width in pixels of this individual glyph.
     ///
     /// Although each PSF has a nominal width in pixels,
@@ -45,6 +126,22 @@ impl Glyph {
     /// The height in pixels of this glyph. This will always be the same as the height of the font.
     pub fn height(&self) -> usize { self.height }
 
+    /// The width in pixels of the glyph's tight ink bounding box,
+    /// i.e. the span between its leftmost and rightmost set columns.
+    /// This is `0` for a blank glyph such as the space.
+    pub fn ink_width(&self) -> usize { self.ink_width }
+    /// The height in pixels of the glyph's tight ink bounding box,
+    /// i.e. the span between its topmost and bottommost set rows.
+    /// This is `0` for a blank glyph such as the space.
+    pub fn ink_height(&self) -> usize { self.ink_height }
+    /// The x offset in pixels of the leftmost set column of the glyph.
+    pub fn bearing_x(&self) -> usize { self.bearing_x }
+    /// The y offset in pixels of the topmost set row of the glyph.
+    pub fn bearing_y(&self) -> usize { self.bearing_y }
+    /// The horizontal pen advance in pixels to use after drawing this glyph.
+    /// This defaults to the font's nominal width.
+    pub fn advance(&self) -> usize { self.advance }
+
     /// Check whether an individual pixel of this glyph is set.
     /// This will return `None` if `x` or `y` is outside the width or height of this glyph.
     pub fn get(&self, x: usize, y: usize) -> Option<bool> {
@@ -57,6 +154,51 @@ impl Glyph {
         let byte = self.bitmap[(y * self.line_size + line_byte_index) as usize];
         Some(byte & mask > 0)
     }
+
+    /// Rasterize this glyph to a coverage bitmap, upscaled by an integer
+    /// `scale` factor with nearest-neighbor sampling.
+    ///
+    /// Returns the width and height in pixels and a row-major buffer with one
+    /// byte per pixel, each `0xFF` for a set pixel or `0x00` for an unset one.
+    /// Because PSF glyphs are bitmaps, nearest-neighbor keeps the edges crisp.
+    pub fn rasterize(&self, scale: usize) -> (usize, usize, Vec<u8>) {
+        let width = self.width * scale;
+        let height = self.height * scale;
+        let mut coverage = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let set = self.get(x / scale, y / scale).unwrap_or(false);
+                coverage.push(if set { 0xFF } else { 0x00 });
+            }
+        }
+        (width, height, coverage)
+    }
+
+    /// Rasterize this glyph like [`Glyph::rasterize`], through a 2× supersample.
+    ///
+    /// The glyph is rendered at twice the requested `scale` and then each 2×2
+    /// block is box-downfiltered into a single coverage byte. The doubled
+    /// rendering is itself a nearest-neighbor upscale, so every 2×2 block is
+    /// uniform: each byte is `0x00` or `0xFF`, the result is the same as
+    /// [`Glyph::rasterize`] at the same `scale`, and edges are not smoothed.
+    pub fn rasterize_antialiased(&self, scale: usize) -> (usize, usize, Vec<u8>) {
+        let (hi_width, _, hi) = self.rasterize(scale * 2);
+        let width = self.width * scale;
+        let height = self.height * scale;
+        let mut coverage = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let mut sum = 0u32;
+                for dy in 0..2 {
+                    for dx in 0..2 {
+                        sum += hi[(y * 2 + dy) * hi_width + (x * 2 + dx)] as u32;
+                    }
+                }
+                coverage.push((sum / 4) as u8);
+            }
+        }
+        (width, height, coverage)
+    }
 }
 
 impl Font {
@@ -75,86 +217,254 @@ impl Font {
         self.index_of(c).map(|i| &self.glyphs[i])
     }
 
+    /// Get the glyph associated with a multi-codepoint sequence such as a base
+    /// letter plus combining diacritics or a ligature, or `None` if the exact
+    /// sequence is not present in this font.
+    ///
+    /// A shaper should match the longest available sequence before falling
+    /// back to [`Font::lookup`] for individual codepoints.
+    pub fn lookup_sequence<'a>(&'a self, chars: &[char]) -> Option<&'a Glyph> {
+        self.sequences
+            .binary_search_by(|entry| entry.chars.as_slice().cmp(chars))
+            .ok()
+            .map(|i| &self.glyphs[self.sequences[i].i])
+    }
+
     /// The index of the glyph associated with a particular unicode character.
     fn index_of(&self, c: char) -> Option<usize> {
-        for entry in &self.unicode {
-            if entry.c == c {
-                return Some(entry.i);
-            }
+        self.unicode
+            .binary_search_by_key(&c, |entry| entry.c)
+            .ok()
+            .map(|i| self.unicode[i].i)
+    }
+
+    /// Parse a PC screen font from its bytes.
+    ///
+    /// The magic number at the start of the data is inspected to decide whether
+    /// it is a version 1 or a version 2 font, and parsing is dispatched
+    /// accordingly. Malformed data yields a descriptive [`ParseError`] rather
+    /// than a panic.
+    pub fn parse(font: &[u8]) -> Result<Font, ParseError> {
+        if font.starts_with(&[0x72, 0xB5, 0x4A, 0x86]) {
+            Font::parse_psf2(font)
+        } else if font.starts_with(&[0x36, 0x04]) {
+            Font::parse_psf1(font)
+        } else {
+            Err(ParseError::BadMagic)
         }
-        None
     }
 
     /// Parse a version 2 PC screen font from its bytes.
-    pub fn parse(font: &[u8]) -> Font {
-        use core::convert::TryInto;
-
+    fn parse_psf2(font: &[u8]) -> Result<Font, ParseError> {
+        // The size in bytes of the header, after which the glyphs begin.
+        let headersize = read_u32(font, 8)?;
         // The number of glyphs in this font.
-        let length = u32::from_le_bytes(font[16..20].try_into().unwrap()) as usize;
+        let length = read_u32(font, 16)?;
         // The size in bytes of a single glyph.
-        let charsize = u32::from_le_bytes(font[20..24].try_into().unwrap()) as usize;
+        let charsize = read_u32(font, 20)?;
         // The height in pixels of this font's bounding box.
-        let height = u32::from_le_bytes(font[24..28].try_into().unwrap()) as usize;
+        let height = read_u32(font, 24)?;
         // The width in pixels of this font's bounding box.
-        let width = u32::from_le_bytes(font[28..32].try_into().unwrap()) as usize;
+        let width = read_u32(font, 28)?;
         // The size in bytes of a single row of pixels in a glyph.
         let line_size = num_integer::div_ceil(width, 8);
 
-        let glyphs_offset = 32; // the size of the header
+        let glyphs_offset = headersize;
         let glyphs_size = length * charsize;
         let unicode_offset = glyphs_offset + glyphs_size;
+        if unicode_offset > font.len() {
+            return Err(ParseError::Truncated);
+        }
+        // Each glyph must hold `height` rows of `line_size` bytes.
+        if charsize < height * line_size {
+            return Err(ParseError::Truncated);
+        }
 
         let mut glyphs = Vec::with_capacity(length);
+        for i in 0..length {
+            let mut bitmap = Vec::with_capacity(charsize);
+            let bitmap_begin = glyphs_offset + charsize * i;
+            let bitmap_end = bitmap_begin + charsize;
+            bitmap.extend_from_slice(&font[bitmap_begin..bitmap_end]);
 
+            // Glyphs may overflow the font's nominal resolution in the padding bytes of the line!
+            // This trick only works for the width because there is no vertical padding.
+            glyphs.push(Glyph::new(bitmap, line_size, line_size * 8, height, width));
+        }
+
+        let (unicode, sequences) = parse_psf2_unicode(&font[unicode_offset..], glyphs.len())?;
+        Ok(Font { width, height, glyphs, unicode, sequences })
+    }
+
+    /// Parse a version 1 PC screen font from its bytes.
+    ///
+    /// PSF1 glyphs are always 8 pixels wide and `charsize` pixels tall, with
+    /// either 256 or 512 glyphs depending on the mode byte.
+    fn parse_psf1(font: &[u8]) -> Result<Font, ParseError> {
+        if font.len() < 4 {
+            return Err(ParseError::Truncated);
+        }
+        // The mode byte selects the glyph count and whether a unicode table follows.
+        let mode = font[2];
+        // The size in bytes of a single glyph, which is also its height in pixels.
+        let charsize = font[3] as usize;
+        let width = 8;
+        let height = charsize;
+        let line_size = 1;
+        let length = if mode & 0x01 != 0 { 512 } else { 256 };
+
+        let glyphs_offset = 4; // the size of the header
+        let glyphs_size = length * charsize;
+        let unicode_offset = glyphs_offset + glyphs_size;
+        if unicode_offset > font.len() {
+            return Err(ParseError::Truncated);
+        }
+
+        let mut glyphs = Vec::with_capacity(length);
         for i in 0..length {
             let mut bitmap = Vec::with_capacity(charsize);
             let bitmap_begin = glyphs_offset + charsize * i;
             let bitmap_end = bitmap_begin + charsize;
             bitmap.extend_from_slice(&font[bitmap_begin..bitmap_end]);
 
-            glyphs.push(Glyph {
-                bitmap,
-                line_size,
-                // Glyphs may overflow the font's nominal resolution in the padding bytes of the line!
-                // This trick only works for the width because there is no vertical padding.
-                // TODO: Pre-compute widths and bounding box offsets of individual glyphs.
-                width: line_size * 8,
-                height,
-            });
-        }
-
-        // HACK: This unicode map parser is still a mess.
-        let mut unicode_map = Vec::new();
-        let unicode_info = &font[unicode_offset..];
-        let mut glyph = 0;
-        let mut i = 0;
-        while i < unicode_info.len() {
-            let mut nc = unicode_info[i];
-
-            while nc != 0xFE && nc != 0xFF {
-                let ch_bytes = nc.leading_ones().max(1) as usize;
-                let st = core::str::from_utf8(&unicode_info[i..i + ch_bytes]).expect("Invalid character");
-                let ch = st.chars().next().unwrap();
-                unicode_map.push(UnicodeMap { c: ch, i: glyph });
-                i += ch_bytes;
-                nc = unicode_info[i];
-            }
+            glyphs.push(Glyph::new(bitmap, line_size, 8, height, width));
+        }
 
-            // TODO: Support multi-codepoint spellings of characters.
-            while nc != 0xFF {
-                i += 1;
-                nc = unicode_info[i];
+        // Mode bits 0x02 and 0x04 both indicate the presence of a unicode table.
+        let (unicode, sequences) = if mode & (0x02 | 0x04) != 0 {
+            parse_psf1_unicode(&font[unicode_offset..], glyphs.len())?
+        } else {
+            (Vec::new(), Vec::new())
+        };
+        Ok(Font { width, height, glyphs, unicode, sequences })
+    }
+}
+
+/// Parse the PSF2 unicode table, which describes each glyph with a run of
+/// UTF-8 codepoints followed by zero or more `0xFE`-introduced sequences,
+/// the whole descriptor terminated by `0xFF`.
+fn parse_psf2_unicode(
+    info: &[u8],
+    glyph_count: usize,
+) -> Result<(Vec<UnicodeMap>, Vec<SequenceMap>), ParseError> {
+    // Read a single UTF-8 codepoint at `i`, advancing it past the character.
+    fn read_char(info: &[u8], i: &mut usize) -> Result<char, ParseError> {
+        let ch_bytes = info[*i].leading_ones().max(1) as usize;
+        if *i + ch_bytes > info.len() {
+            return Err(ParseError::Truncated);
+        }
+        let st = core::str::from_utf8(&info[*i..*i + ch_bytes]).map_err(|_| ParseError::InvalidUtf8)?;
+        let ch = st.chars().next().ok_or(ParseError::InvalidUtf8)?;
+        *i += ch_bytes;
+        Ok(ch)
+    }
+
+    let mut unicode_map = Vec::new();
+    let mut sequences = Vec::new();
+    let mut glyph = 0;
+    let mut i = 0;
+    while i < info.len() {
+        if glyph >= glyph_count {
+            return Err(ParseError::GlyphIndexOutOfRange(glyph));
+        }
+
+        // The single-codepoint spellings of this glyph.
+        while info[i] != 0xFE && info[i] != 0xFF {
+            let ch = read_char(info, &mut i)?;
+            unicode_map.push(UnicodeMap { c: ch, i: glyph });
+            if i >= info.len() {
+                return Err(ParseError::Truncated);
             }
+        }
 
+        // The multi-codepoint spellings, each introduced by a `0xFE` byte.
+        while info[i] == 0xFE {
             i += 1;
-            glyph += 1;
+            let mut seq = Vec::new();
+            while i < info.len() && info[i] != 0xFE && info[i] != 0xFF {
+                seq.push(read_char(info, &mut i)?);
+            }
+            if i >= info.len() {
+                return Err(ParseError::Truncated);
+            }
+            register_sequence(&mut unicode_map, &mut sequences, seq, glyph);
         }
 
-        Font {
-            width,
-            height,
-            glyphs,
-            unicode: unicode_map,
+        i += 1; // consume the terminating 0xFF
+        glyph += 1;
+    }
+    unicode_map.sort_by_key(|entry| entry.c);
+    sequences.sort_by(|a, b| a.chars.cmp(&b.chars));
+    Ok((unicode_map, sequences))
+}
+
+/// Record a parsed sequence, also registering a one-element sequence as an
+/// ordinary single-codepoint spelling so that [`Font::lookup`] keeps working.
+fn register_sequence(
+    unicode_map: &mut Vec<UnicodeMap>,
+    sequences: &mut Vec<SequenceMap>,
+    seq: Vec<char>,
+    glyph: usize,
+) {
+    match seq.as_slice() {
+        [] => {}
+        [c] => unicode_map.push(UnicodeMap { c: *c, i: glyph }),
+        _ => sequences.push(SequenceMap { chars: seq, i: glyph }),
+    }
+}
+
+/// Parse the PSF1 unicode table, which describes each glyph with a run of
+/// little-endian `u16` codepoints followed by zero or more `0xFFFE`-introduced
+/// sequences, the whole descriptor terminated by `0xFFFF`.
+fn parse_psf1_unicode(
+    info: &[u8],
+    glyph_count: usize,
+) -> Result<(Vec<UnicodeMap>, Vec<SequenceMap>), ParseError> {
+    let code = |i: usize| u16::from_le_bytes([info[i], info[i + 1]]);
+
+    let mut unicode_map = Vec::new();
+    let mut sequences = Vec::new();
+    let mut glyph = 0;
+    let mut i = 0;
+    while i + 1 < info.len() {
+        if glyph >= glyph_count {
+            return Err(ParseError::GlyphIndexOutOfRange(glyph));
         }
+
+        // The single-codepoint spellings of this glyph.
+        while code(i) != 0xFFFE && code(i) != 0xFFFF {
+            if let Some(ch) = char::from_u32(code(i) as u32) {
+                unicode_map.push(UnicodeMap { c: ch, i: glyph });
+            }
+            i += 2;
+            if i + 1 >= info.len() {
+                return Err(ParseError::Truncated);
+            }
+        }
+
+        // The multi-codepoint spellings, each introduced by a `0xFFFE` marker.
+        while code(i) == 0xFFFE {
+            i += 2;
+            if i + 1 >= info.len() {
+                return Err(ParseError::Truncated);
+            }
+            let mut seq = Vec::new();
+            while code(i) != 0xFFFE && code(i) != 0xFFFF {
+                if let Some(ch) = char::from_u32(code(i) as u32) {
+                    seq.push(ch);
+                }
+                i += 2;
+                if i + 1 >= info.len() {
+                    return Err(ParseError::Truncated);
+                }
+            }
+            register_sequence(&mut unicode_map, &mut sequences, seq, glyph);
+        }
+
+        i += 2; // consume the terminating 0xFFFF
+        glyph += 1;
     }
+    unicode_map.sort_by_key(|entry| entry.c);
+    sequences.sort_by(|a, b| a.chars.cmp(&b.chars));
+    Ok((unicode_map, sequences))
 }